@@ -0,0 +1,234 @@
+//! `DocSet`: a cursor over a sorted, deduplicated stream of doc ids, modeled
+//! on tantivy's trait of the same name. Multi-term `AND` queries leapfrog
+//! several `DocSet`s toward agreement instead of materializing and
+//! intersecting whole `Vec<DocId>`s.
+
+use crate::Posting;
+
+pub type DocId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekResult {
+    /// The cursor landed exactly on `target`.
+    Reached,
+    /// `target` wasn't present; the cursor now sits on the next id after it.
+    Overstep,
+    /// The doc set is exhausted; there is no id `>= target`.
+    End,
+}
+
+pub trait DocSet {
+    /// Moves to the next doc id in the set, or `None` once exhausted.
+    fn advance(&mut self) -> Option<DocId>;
+
+    /// Moves the cursor to the first doc id `>= target`.
+    fn seek(&mut self, target: DocId) -> SeekResult;
+
+    /// The doc id the cursor currently sits on, if any.
+    fn current(&self) -> Option<DocId>;
+}
+
+/// A `DocSet` over one term's postings (already sorted by doc id, since
+/// `IndexWriter::add` appends in increasing doc-id order). `seek` binary
+/// searches the remaining slice rather than scanning it - the postings are
+/// already a random-access, doc-id-sorted slice by the time a
+/// `PostingsDocSet` sees it, so no separate skip structure is needed to
+/// get O(log n) jumps.
+///
+/// Note this only bounds in-memory seek cost, not I/O: `Index::read` decodes
+/// every posting for a term up front regardless, so there are no on-disk
+/// skip pointers (byte offsets into the encoded postings blob) to let a
+/// reader jump without first decoding the whole list. Adding those would
+/// need a format change in `Index::write`/`Index::read`, not just this type.
+pub struct PostingsDocSet<'a> {
+    postings: &'a [Posting],
+    pos: Option<usize>,
+}
+
+impl<'a> PostingsDocSet<'a> {
+    pub fn new(postings: &'a [Posting]) -> Self {
+        PostingsDocSet { postings, pos: None }
+    }
+}
+
+impl<'a> DocSet for PostingsDocSet<'a> {
+    fn advance(&mut self) -> Option<DocId> {
+        let next = self.pos.map_or(0, |pos| pos + 1);
+        if next >= self.postings.len() {
+            self.pos = Some(self.postings.len());
+            return None;
+        }
+
+        self.pos = Some(next);
+        Some(self.postings[next].doc_id)
+    }
+
+    fn seek(&mut self, target: DocId) -> SeekResult {
+        if let Some(doc_id) = self.current() {
+            if doc_id == target {
+                return SeekResult::Reached;
+            } else if doc_id > target {
+                return SeekResult::Overstep;
+            }
+        }
+
+        // The remainder of the slice is still doc-id sorted, so jump
+        // straight to `target`'s position instead of scanning for it.
+        // Clamp to the slice length: once exhausted, `pos + 1` can run past
+        // the end, and a repeated seek should keep reporting `End` rather
+        // than panic on an out-of-range slice.
+        let start = self.pos.map_or(0, |pos| pos + 1).min(self.postings.len());
+        let index = match self.postings[start..].binary_search_by(|posting| posting.doc_id.cmp(&target)) {
+            Ok(offset) | Err(offset) => start + offset,
+        };
+
+        if index >= self.postings.len() {
+            self.pos = Some(self.postings.len());
+            return SeekResult::End;
+        }
+
+        self.pos = Some(index);
+        if self.postings[index].doc_id == target {
+            SeekResult::Reached
+        } else {
+            SeekResult::Overstep
+        }
+    }
+
+    fn current(&self) -> Option<DocId> {
+        match self.pos {
+            Some(pos) if pos < self.postings.len() => Some(self.postings[pos].doc_id),
+            _ => None,
+        }
+    }
+}
+
+/// Intersects several `DocSet`s via leapfrogging: each round, every cursor
+/// seeks to the highest current doc id among them; if they all land on it,
+/// it's part of the intersection. Doc ids only increase, so this terminates
+/// in a number of seeks bounded by the shortest set's length times the
+/// number of sets.
+pub fn leapfrog_intersect(mut sets: Vec<PostingsDocSet>) -> Vec<DocId> {
+    if sets.is_empty() {
+        return Vec::new();
+    }
+
+    for set in sets.iter_mut() {
+        if set.advance().is_none() {
+            return Vec::new();
+        }
+    }
+
+    let mut results = Vec::new();
+    loop {
+        let target = match sets.iter().filter_map(DocSet::current).max() {
+            Some(target) => target,
+            None => break,
+        };
+
+        let mut all_reached = true;
+        for set in sets.iter_mut() {
+            match set.seek(target) {
+                SeekResult::Reached => {}
+                SeekResult::Overstep => all_reached = false,
+                SeekResult::End => return results,
+            }
+        }
+
+        if all_reached {
+            results.push(target);
+            if sets.iter_mut().any(|set| set.advance().is_none()) {
+                break;
+            }
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn postings(doc_ids: &[usize]) -> Vec<Posting> {
+        doc_ids
+            .iter()
+            .map(|&doc_id| Posting {
+                doc_id,
+                positions: vec![0],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn advance_walks_in_order() {
+        let postings = postings(&[1, 3, 5]);
+        let mut set = PostingsDocSet::new(&postings);
+
+        assert_eq!(Some(1), set.advance());
+        assert_eq!(Some(3), set.advance());
+        assert_eq!(Some(5), set.advance());
+        assert_eq!(None, set.advance());
+    }
+
+    #[test]
+    fn seek_finds_reached_and_overstep() {
+        let postings = postings(&[1, 3, 5, 9]);
+        let mut set = PostingsDocSet::new(&postings);
+
+        assert_eq!(SeekResult::Reached, set.seek(5));
+        assert_eq!(Some(5), set.current());
+        assert_eq!(SeekResult::Overstep, set.seek(6));
+        assert_eq!(Some(9), set.current());
+        assert_eq!(SeekResult::End, set.seek(100));
+    }
+
+    #[test]
+    fn seek_after_exhaustion_returns_end_instead_of_panicking() {
+        let postings = postings(&[1, 3, 5]);
+        let mut set = PostingsDocSet::new(&postings);
+
+        assert_eq!(SeekResult::End, set.seek(100));
+        assert_eq!(SeekResult::End, set.seek(100));
+    }
+
+    #[test]
+    fn seek_finds_reached_and_overstep_on_long_lists() {
+        let doc_ids: Vec<usize> = (0..1000).map(|i| i * 2).collect();
+        let postings = postings(&doc_ids);
+        let mut set = PostingsDocSet::new(&postings);
+
+        assert_eq!(SeekResult::Reached, set.seek(998));
+        assert_eq!(Some(998), set.current());
+        assert_eq!(SeekResult::Overstep, set.seek(999));
+    }
+
+    #[test]
+    fn leapfrog_intersect_finds_common_doc_ids() {
+        let postings_a = postings(&[1, 2, 3, 4, 5]);
+        let postings_b = postings(&[2, 3, 5, 8]);
+        let postings_c = postings(&[2, 3, 4, 6]);
+
+        let sets = vec![
+            PostingsDocSet::new(&postings_a),
+            PostingsDocSet::new(&postings_b),
+            PostingsDocSet::new(&postings_c),
+        ];
+
+        assert_eq!(vec![2, 3], leapfrog_intersect(sets));
+    }
+
+    #[test]
+    fn leapfrog_intersect_empty_when_one_set_is_empty() {
+        let postings_a = postings(&[1, 2, 3]);
+        let postings_b: Vec<Posting> = Vec::new();
+
+        let sets = vec![
+            PostingsDocSet::new(&postings_a),
+            PostingsDocSet::new(&postings_b),
+        ];
+
+        let empty: Vec<DocId> = Vec::new();
+        assert_eq!(empty, leapfrog_intersect(sets));
+    }
+}
@@ -0,0 +1,230 @@
+//! Spill/reload for the documents `IndexWriter` holds once `with_memory_limit`
+//! is exceeded, mirroring `merge`'s postings segments. Unlike postings, doc
+//! ids only ever increase and segments are flushed in that same order, so no
+//! term-keyed merge is needed - reading segments back in flush order and
+//! concatenating them reconstructs the full, correctly ordered document
+//! list.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs,
+    io::{self, prelude::*},
+    path::{Path, PathBuf},
+};
+
+use snafu::{ResultExt, Snafu};
+
+use crate::{read_u16, read_u32, Document};
+
+#[derive(Debug, Snafu)]
+pub enum DocStoreError {
+    UnableToCreateDocSegmentFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    UnableToOpenDocSegmentFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    UnableToWriteDocSegmentEntry {
+        source: io::Error,
+    },
+    UnableToFlushDocSegmentFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    UnableToReadDocSegmentEntry {
+        source: io::Error,
+    },
+    UnableToDownCastDocSegmentValue {
+        source: core::num::TryFromIntError,
+    },
+}
+
+/// Writes one segment file: every doc in `docs`, in order, each in the same
+/// shape the final index file uses for a document (see `crate::IndexWriter::write`).
+pub fn write_segment(path: &Path, docs: &[Document]) -> Result<(), DocStoreError> {
+    let file = fs::File::create(path).context(UnableToCreateDocSegmentFile { path })?;
+    let mut writer = io::BufWriter::new(file);
+
+    for doc in docs {
+        write_doc_block(&mut writer, doc)?;
+    }
+
+    writer.flush().context(UnableToFlushDocSegmentFile { path })?;
+
+    Ok(())
+}
+
+/// Streams every doc segment straight into `out`, in flush (i.e. doc-id)
+/// order - at most one document is ever held in memory at once.
+pub fn write_merged_docs<W: Write>(paths: &[PathBuf], out: &mut W) -> Result<(), DocStoreError> {
+    for path in paths {
+        let mut reader = open_reader(path)?;
+        while has_more(&mut reader)? {
+            let doc = read_doc_block(&mut reader)?;
+            write_doc_block(out, &doc)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads every doc segment back into memory, in flush order - used when an
+/// `IndexWriter` that spilled to disk is converted straight into an `Index`
+/// (see `From<IndexWriter> for Index`) rather than written out and read
+/// back.
+pub fn read_merged_docs(paths: &[PathBuf]) -> Result<Vec<Document>, DocStoreError> {
+    let mut docs = Vec::new();
+
+    for path in paths {
+        let mut reader = open_reader(path)?;
+        while has_more(&mut reader)? {
+            docs.push(read_doc_block(&mut reader)?);
+        }
+    }
+
+    Ok(docs)
+}
+
+fn open_reader(path: &Path) -> Result<io::BufReader<fs::File>, DocStoreError> {
+    let file = fs::File::open(path).context(UnableToOpenDocSegmentFile { path })?;
+    Ok(io::BufReader::new(file))
+}
+
+fn has_more(reader: &mut impl BufRead) -> Result<bool, DocStoreError> {
+    Ok(!reader
+        .fill_buf()
+        .context(UnableToReadDocSegmentEntry)?
+        .is_empty())
+}
+
+fn write_doc_block<W: Write>(writer: &mut W, doc: &Document) -> Result<(), DocStoreError> {
+    let word_length = u32::try_from(doc.length).context(UnableToDownCastDocSegmentValue)?;
+    writer
+        .write_all(&word_length.to_be_bytes())
+        .context(UnableToWriteDocSegmentEntry)?;
+
+    let content_bytes = doc.content.as_bytes();
+    let content_length =
+        u32::try_from(content_bytes.len()).context(UnableToDownCastDocSegmentValue)?;
+    writer
+        .write_all(&content_length.to_be_bytes())
+        .context(UnableToWriteDocSegmentEntry)?;
+    writer
+        .write_all(content_bytes)
+        .context(UnableToWriteDocSegmentEntry)?;
+
+    let num_stored_fields =
+        u32::try_from(doc.stored.len()).context(UnableToDownCastDocSegmentValue)?;
+    writer
+        .write_all(&num_stored_fields.to_be_bytes())
+        .context(UnableToWriteDocSegmentEntry)?;
+
+    for (field, value) in &doc.stored {
+        let field_bytes = field.as_bytes();
+        let field_length =
+            u16::try_from(field_bytes.len()).context(UnableToDownCastDocSegmentValue)?;
+        writer
+            .write_all(&field_length.to_be_bytes())
+            .context(UnableToWriteDocSegmentEntry)?;
+        writer
+            .write_all(field_bytes)
+            .context(UnableToWriteDocSegmentEntry)?;
+
+        let value_bytes = value.as_bytes();
+        let value_length =
+            u32::try_from(value_bytes.len()).context(UnableToDownCastDocSegmentValue)?;
+        writer
+            .write_all(&value_length.to_be_bytes())
+            .context(UnableToWriteDocSegmentEntry)?;
+        writer
+            .write_all(value_bytes)
+            .context(UnableToWriteDocSegmentEntry)?;
+    }
+
+    Ok(())
+}
+
+fn read_doc_block(reader: &mut impl BufRead) -> Result<Document, DocStoreError> {
+    let length = read_u32(reader).context(UnableToReadDocSegmentEntry)? as usize;
+    let content_size = read_u32(reader).context(UnableToReadDocSegmentEntry)?;
+
+    let mut content = String::new();
+    {
+        let mut limited = reader.by_ref().take(content_size as u64);
+        limited
+            .read_to_string(&mut content)
+            .context(UnableToReadDocSegmentEntry)?;
+    }
+
+    let num_stored_fields = read_u32(reader).context(UnableToReadDocSegmentEntry)?;
+    let mut stored = HashMap::with_capacity(num_stored_fields as usize);
+    for _ in 0..num_stored_fields {
+        let name_size = read_u16(reader).context(UnableToReadDocSegmentEntry)?;
+        let mut name = String::new();
+        {
+            let mut limited = reader.by_ref().take(name_size as u64);
+            limited
+                .read_to_string(&mut name)
+                .context(UnableToReadDocSegmentEntry)?;
+        }
+
+        let value_size = read_u32(reader).context(UnableToReadDocSegmentEntry)?;
+        let mut value = String::new();
+        {
+            let mut limited = reader.by_ref().take(value_size as u64);
+            limited
+                .read_to_string(&mut value)
+                .context(UnableToReadDocSegmentEntry)?;
+        }
+
+        stored.insert(name, value);
+    }
+
+    Ok(Document {
+        content,
+        length,
+        stored,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(content: &str, stored: &[(&str, &str)]) -> Document {
+        Document {
+            content: content.to_string(),
+            length: content.split_whitespace().count(),
+            stored: stored
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn round_trips_docs_across_segments_in_flush_order() {
+        let dir = std::env::temp_dir().join(format!("rsearch-docstore-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let first = vec![doc("cats and dogs", &[("title", "Pets")])];
+        let second = vec![doc("birds sing", &[]), doc("snakes hiss", &[("title", "Reptiles")])];
+
+        let first_path = dir.join("0.bin");
+        let second_path = dir.join("1.bin");
+        write_segment(&first_path, &first).unwrap();
+        write_segment(&second_path, &second).unwrap();
+
+        let paths = vec![first_path, second_path];
+        let merged = read_merged_docs(&paths).unwrap();
+
+        let mut expected = first;
+        expected.extend(second);
+        assert_eq!(expected, merged);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
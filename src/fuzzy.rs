@@ -0,0 +1,120 @@
+//! Typo-tolerant term lookup.
+//!
+//! A `HashMap` can only be probed for exact keys, so fuzzy lookups build a
+//! trie over the indexed terms and walk it while carrying a Levenshtein DP
+//! row, pruning any subtree whose row can no longer reach within
+//! `max_distance` of the query. This is the classic bounded edit-distance
+//! trie walk (see MeiliSearch's Levenshtein-automaton term search).
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, PartialEq)]
+struct TrieNode {
+    children: BTreeMap<char, TrieNode>,
+    is_word: bool,
+}
+
+impl TrieNode {
+    fn insert(&mut self, term: &str) {
+        let mut node = self;
+        for c in term.chars() {
+            node = node.children.entry(c).or_insert_with(TrieNode::default);
+        }
+        node.is_word = true;
+    }
+}
+
+/// A trie over the index's term dictionary, used for bounded edit-distance
+/// ("fuzzy") term lookups.
+#[derive(Debug, Default, PartialEq)]
+pub struct TermTrie {
+    root: TrieNode,
+}
+
+impl TermTrie {
+    pub fn build<'a>(terms: impl Iterator<Item = &'a String>) -> Self {
+        let mut trie = TermTrie::default();
+        for term in terms {
+            trie.root.insert(term);
+        }
+        trie
+    }
+
+    /// Returns every indexed term within `max_distance` edits of `query`.
+    pub fn fuzzy_matches(&self, query: &str, max_distance: usize) -> Vec<String> {
+        let query: Vec<char> = query.chars().collect();
+        let initial_row: Vec<usize> = (0..=query.len()).collect();
+
+        let mut matches = Vec::new();
+        let mut prefix = String::new();
+        Self::walk(
+            &self.root,
+            &query,
+            &initial_row,
+            max_distance,
+            &mut prefix,
+            &mut matches,
+        );
+        matches
+    }
+
+    fn walk(
+        node: &TrieNode,
+        query: &[char],
+        prev_row: &[usize],
+        max_distance: usize,
+        prefix: &mut String,
+        matches: &mut Vec<String>,
+    ) {
+        if node.is_word && prev_row.last().map_or(false, |&d| d <= max_distance) {
+            matches.push(prefix.clone());
+        }
+
+        for (&c, child) in &node.children {
+            let mut row = Vec::with_capacity(prev_row.len());
+            row.push(prev_row[0] + 1);
+            for i in 1..prev_row.len() {
+                let substitution_cost = if query[i - 1] == c { 0 } else { 1 };
+                let insertion = row[i - 1] + 1;
+                let deletion = prev_row[i] + 1;
+                let substitution = prev_row[i - 1] + substitution_cost;
+                row.push(insertion.min(deletion).min(substitution));
+            }
+
+            if row.iter().min().unwrap() <= &max_distance {
+                prefix.push(c);
+                Self::walk(child, query, &row, max_distance, prefix, matches);
+                prefix.pop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trie(terms: &[&str]) -> TermTrie {
+        let owned: Vec<String> = terms.iter().map(|t| t.to_string()).collect();
+        TermTrie::build(owned.iter())
+    }
+
+    #[test]
+    fn exact_match_has_distance_zero() {
+        let trie = trie(&["receive"]);
+        assert_eq!(vec!["receive".to_string()], trie.fuzzy_matches("receive", 0));
+    }
+
+    #[test]
+    fn finds_transposed_letters_within_distance_two() {
+        let trie = trie(&["receive"]);
+        assert_eq!(vec!["receive".to_string()], trie.fuzzy_matches("recieve", 2));
+        assert!(trie.fuzzy_matches("recieve", 0).is_empty());
+    }
+
+    #[test]
+    fn prunes_far_terms() {
+        let trie = trie(&["receive", "xyzzy"]);
+        assert_eq!(vec!["receive".to_string()], trie.fuzzy_matches("recieve", 2));
+    }
+}
@@ -4,8 +4,49 @@ use itertools::Itertools;
 use snafu::{ResultExt, Snafu};
 use unicode_segmentation::UnicodeSegmentation;
 
+mod docset;
+mod docstore;
+mod fuzzy;
+mod merge;
+mod query;
+pub mod source;
+mod tmp;
+pub use query::Op;
+
+use docset::{leapfrog_intersect, PostingsDocSet};
+use fuzzy::TermTrie;
+use std::path::PathBuf;
+use tmp::TmpDir;
+
+/// Identifies an rsearch index file, written first so the reader can tell a
+/// real index from garbage before trying to parse one.
+const FORMAT_MAGIC: u32 = 0x5253_4458; // "RSDX"
+
+/// Bumped whenever the on-disk layout changes, so `Index::read` can reject
+/// files written by an incompatible version instead of misparsing them.
+/// v1: added document length (word count) alongside content, for BM25.
+/// v2: doc ids within a posting list are delta + variable-byte encoded
+/// instead of raw `u32`s, since they're already ascending.
+/// v3: documents optionally carry stored fields (name -> value) alongside
+/// content, for structured sources like CSV/JSONL - see `source` module.
+const FORMAT_VERSION: u32 = 3;
+
+/// BM25 term-frequency saturation parameter. See `Index::search_bm25`.
+const BM25_K1: f32 = 1.2;
+/// BM25 document-length normalization parameter. See `Index::search_bm25`.
+const BM25_B: f32 = 0.75;
+
 #[derive(Debug, Snafu)]
 pub enum IndexError {
+    UnableToReadMagic {
+        source: io::Error,
+    },
+    UnsupportedFormatVersion {
+        found: u32,
+    },
+    UnableToReadFormatVersion {
+        source: io::Error,
+    },
     UnableToReadPostingListSize {
         source: io::Error,
     },
@@ -17,7 +58,7 @@ pub enum IndexError {
         term_id: u32,
         source: io::Error,
     },
-    UnableToReadNumberOfDocIds {
+    UnableToReadNumberOfPostings {
         term: String,
         term_id: u32,
         source: io::Error,
@@ -25,12 +66,29 @@ pub enum IndexError {
     UnableToReadDocId {
         term: String,
         term_id: u32,
-        doc_index: u32,
+        posting_index: u32,
+        source: io::Error,
+    },
+    UnableToReadNumberOfPositions {
+        term: String,
+        term_id: u32,
+        posting_index: u32,
+        source: io::Error,
+    },
+    UnableToReadPosition {
+        term: String,
+        term_id: u32,
+        posting_index: u32,
+        position_index: u32,
         source: io::Error,
     },
     UnableToReadNumberOfDocs {
         source: io::Error,
     },
+    UnableToReadDocLength {
+        doc_id: u32,
+        source: io::Error,
+    },
     UnableToReadDocSize {
         doc_id: u32,
         source: io::Error,
@@ -39,43 +97,156 @@ pub enum IndexError {
         doc_id: u32,
         source: io::Error,
     },
+    UnableToReadNumberOfStoredFields {
+        doc_id: u32,
+        source: io::Error,
+    },
+    UnableToReadStoredFieldNameSize {
+        doc_id: u32,
+        field_index: u32,
+        source: io::Error,
+    },
+    UnableToReadStoredFieldName {
+        doc_id: u32,
+        field_index: u32,
+        source: io::Error,
+    },
+    UnableToReadStoredFieldValueSize {
+        doc_id: u32,
+        field_index: u32,
+        source: io::Error,
+    },
+    UnableToReadStoredFieldValue {
+        doc_id: u32,
+        field_index: u32,
+        source: io::Error,
+    },
 }
 
-fn read_u32(reader: &mut impl io::BufRead) -> Result<u32, io::Error> {
+pub(crate) fn read_u32(reader: &mut impl io::BufRead) -> Result<u32, io::Error> {
     let mut buf = [0 as u8; 4];
     reader.read_exact(&mut buf)?;
 
     Ok(u32::from_be_bytes(buf))
 }
 
-fn read_u16(reader: &mut impl io::BufRead) -> Result<u16, io::Error> {
+pub(crate) fn read_u16(reader: &mut impl io::BufRead) -> Result<u16, io::Error> {
     let mut buf = [0 as u8; 2];
     reader.read_exact(&mut buf)?;
 
     Ok(u16::from_be_bytes(buf))
 }
 
+/// Reads a variable-byte (LEB128-style) encoded `u32`: 7 bits of value per
+/// byte, low-to-high, with the high bit set on every byte but the last.
+pub(crate) fn read_varint(reader: &mut impl io::BufRead) -> Result<u32, io::Error> {
+    let mut value: u32 = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0 as u8; 1];
+        reader.read_exact(&mut byte)?;
+        value |= u32::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Writes `value` as a variable-byte (LEB128-style) encoded `u32`. See
+/// `read_varint`.
+pub(crate) fn write_varint(writer: &mut impl io::Write, mut value: u32) -> Result<(), io::Error> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
 use std::collections::HashMap;
-type PostingsList = HashMap<String, Vec<usize>>;
+
+/// One term's occurrence within a single document: which doc it's in, and
+/// every (0-based, word-level) position it appears at. Phrase queries walk
+/// `positions` to check adjacency between terms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    pub doc_id: usize,
+    pub positions: Vec<usize>,
+}
+
+type PostingsList = HashMap<String, Vec<Posting>>;
 
 #[derive(PartialEq, Debug)]
 pub struct Document {
     pub content: String,
+    // Number of terms in the document, i.e. the highest position plus one.
+    // Stored rather than recomputed so BM25 scoring doesn't have to re-tokenize.
+    pub length: usize,
+    /// Fields carried through from a structured `source::Record` (CSV,
+    /// JSONL) that are displayed but not tokenized into the postings list
+    /// - e.g. a JSON record's `title` alongside its indexed `body`. Empty
+    /// for documents built straight from `analyze`.
+    pub stored: HashMap<String, String>,
 }
 
-type TermList = HashSet<String>;
+/// Term -> positions the term occurs at within one document, produced by
+/// `analyze` and consumed by `IndexWriter::add`.
+type TermPositions = HashMap<String, Vec<usize>>;
 
 pub struct AnalyzedDocument {
-    terms: TermList,
+    term_positions: TermPositions,
     content: String,
+    length: usize,
+    stored: HashMap<String, String>,
 }
 
 pub fn analyze(content: String) -> AnalyzedDocument {
-    let terms = content
-        .unicode_words()
-        .map(|w| w.to_lowercase().to_string())
+    let mut term_positions: TermPositions = HashMap::new();
+    let mut length = 0;
+    for (position, word) in content.unicode_words().enumerate() {
+        term_positions
+            .entry(word.to_lowercase())
+            .or_insert_with(Vec::new)
+            .push(position);
+        length = position + 1;
+    }
+
+    AnalyzedDocument {
+        term_positions,
+        content,
+        length,
+        stored: HashMap::new(),
+    }
+}
+
+/// Builds an `AnalyzedDocument` from one `source::Record`: every
+/// `searchable` field's value is concatenated (in order, space-separated)
+/// and tokenized exactly like `analyze` would a whole document, and every
+/// `stored` field is carried through untouched onto `Document::stored`.
+pub fn analyze_record(
+    record: source::Record,
+    searchable: &[String],
+    stored: &[String],
+) -> AnalyzedDocument {
+    let mut content = String::new();
+    for field in searchable {
+        if let Some(value) = record.fields.get(field) {
+            if !content.is_empty() {
+                content.push(' ');
+            }
+            content.push_str(value);
+        }
+    }
+
+    let mut analyzed = analyze(content);
+    analyzed.stored = stored
+        .iter()
+        .filter_map(|field| record.fields.get(field).map(|value| (field.clone(), value.clone())))
         .collect();
-    AnalyzedDocument { terms, content }
+    analyzed
 }
 
 #[derive(Debug, PartialEq)]
@@ -87,6 +258,54 @@ pub struct Index {
     // but would mean that we need to increment our own counter rather
     // than using the vector size.
     postings: PostingsList,
+    // Built fresh any time the postings change (read or From<IndexWriter>) so
+    // fuzzy lookups don't have to scan every key in the postings map.
+    term_trie: TermTrie,
+    // Corpus average document length, for BM25's length-normalization term.
+    avg_doc_length: f32,
+}
+
+fn avg_doc_length(docs: &[Document]) -> f32 {
+    if docs.is_empty() {
+        return 0.0;
+    }
+
+    let total: usize = docs.iter().map(|doc| doc.length).sum();
+    total as f32 / docs.len() as f32
+}
+
+/// Rough in-memory footprint of one term's postings entry for one document:
+/// the term's bytes (charged every time, like a real `HashMap` would on
+/// first insert, since we're only estimating), the `Posting` struct itself,
+/// and one `usize` per position. Good enough to decide when to spill a
+/// segment to disk - not meant to be exact.
+fn estimated_posting_bytes(term: &str, positions: &[usize]) -> usize {
+    term.len() + std::mem::size_of::<Posting>() + positions.len() * std::mem::size_of::<usize>()
+}
+
+/// Rough in-memory footprint of one document: its content and stored
+/// fields (typically the dominant cost for a corpus like a maildir), plus
+/// the `Document` struct itself. Good enough to decide when to spill a
+/// segment to disk - not meant to be exact.
+fn estimated_doc_bytes(doc: &Document) -> usize {
+    doc.content.len()
+        + doc
+            .stored
+            .iter()
+            .map(|(field, value)| field.len() + value.len())
+            .sum::<usize>()
+        + std::mem::size_of::<Document>()
+}
+
+/// If every operand is a bare term, returns them so the AND can leapfrog
+/// postings directly instead of evaluating each operand into a HashSet.
+fn as_plain_terms(ops: &[Op]) -> Option<Vec<&str>> {
+    ops.iter()
+        .map(|op| match op {
+            Op::Term(term) => Some(term.as_str()),
+            _ => None,
+        })
+        .collect()
 }
 
 impl Index {
@@ -96,6 +315,12 @@ impl Index {
     {
         let mut reader = io::BufReader::new(reader);
 
+        let magic = read_u32(&mut reader).context(UnableToReadMagic)?;
+        let version = read_u32(&mut reader).context(UnableToReadFormatVersion)?;
+        if magic != FORMAT_MAGIC || version != FORMAT_VERSION {
+            return UnsupportedFormatVersion { found: version }.fail();
+        }
+
         // First, postings size
         let num_terms = read_u32(&mut reader).context(UnableToReadPostingListSize)?;
         let mut postings =
@@ -113,30 +338,59 @@ impl Index {
                     .context(UnableToReadTerm { term_id })?;
             }
 
-            // Then the number of doc ids and the doc ids themselves
-            let num_doc_ids = read_u32(&mut reader).context(UnableToReadNumberOfDocIds {
+            // Then the number of postings and the postings themselves
+            let num_postings = read_u32(&mut reader).context(UnableToReadNumberOfPostings {
                 term: &term,
                 term_id,
             })?;
 
-            let mut doc_ids: Vec<usize> = Vec::with_capacity(num_doc_ids as usize);
+            let mut postings_for_term: Vec<Posting> = Vec::with_capacity(num_postings as usize);
 
-            for doc_index in 0..num_doc_ids {
-                let doc_id = read_u32(&mut reader).context(UnableToReadDocId {
+            // Doc ids are stored as gaps from the previous doc id in this
+            // term's list (ascending, so gaps are always >= 0), so the
+            // running sum below reconstructs the absolute ids.
+            let mut prev_doc_id: u32 = 0;
+            for posting_index in 0..num_postings {
+                let gap = read_varint(&mut reader).context(UnableToReadDocId {
                     term: &term,
                     term_id,
-                    doc_index,
+                    posting_index,
                 })?;
-                doc_ids.push(doc_id as usize);
+                let doc_id = prev_doc_id + gap;
+                prev_doc_id = doc_id;
+
+                let num_positions =
+                    read_u32(&mut reader).context(UnableToReadNumberOfPositions {
+                        term: &term,
+                        term_id,
+                        posting_index,
+                    })?;
+
+                let mut positions: Vec<usize> = Vec::with_capacity(num_positions as usize);
+                for position_index in 0..num_positions {
+                    let position = read_u32(&mut reader).context(UnableToReadPosition {
+                        term: &term,
+                        term_id,
+                        posting_index,
+                        position_index,
+                    })?;
+                    positions.push(position as usize);
+                }
+
+                postings_for_term.push(Posting {
+                    doc_id: doc_id as usize,
+                    positions,
+                });
             }
 
-            postings.insert(term, doc_ids);
+            postings.insert(term, postings_for_term);
         }
 
         let num_docs = read_u32(&mut reader).context(UnableToReadNumberOfDocs)?;
 
         let mut docs: Vec<Document> = Vec::with_capacity(num_docs as usize);
         for doc_id in 0..num_docs {
+            let length = read_u32(&mut reader).context(UnableToReadDocLength { doc_id })? as usize;
             let content_size = read_u32(&mut reader).context(UnableToReadDocSize { doc_id })?;
 
             let mut content = String::new();
@@ -147,10 +401,45 @@ impl Index {
                     .context(UnableToReadDocContent { doc_id })?;
             }
 
-            docs.push(Document { content })
+            let num_stored_fields =
+                read_u32(&mut reader).context(UnableToReadNumberOfStoredFields { doc_id })?;
+            let mut stored = HashMap::with_capacity(num_stored_fields as usize);
+            for field_index in 0..num_stored_fields {
+                let name_size = read_u16(&mut reader)
+                    .context(UnableToReadStoredFieldNameSize { doc_id, field_index })?;
+                let mut name = String::new();
+                {
+                    let mut limited_reader = reader.by_ref().take(name_size as u64);
+                    limited_reader
+                        .read_to_string(&mut name)
+                        .context(UnableToReadStoredFieldName { doc_id, field_index })?;
+                }
+
+                let value_size = read_u32(&mut reader)
+                    .context(UnableToReadStoredFieldValueSize { doc_id, field_index })?;
+                let mut value = String::new();
+                {
+                    let mut limited_reader = reader.by_ref().take(value_size as u64);
+                    limited_reader
+                        .read_to_string(&mut value)
+                        .context(UnableToReadStoredFieldValue { doc_id, field_index })?;
+                }
+
+                stored.insert(name, value);
+            }
+
+            docs.push(Document { content, length, stored })
         }
 
-        Ok(Index { postings, docs })
+        let term_trie = TermTrie::build(postings.keys());
+        let avg_doc_length = avg_doc_length(&docs);
+
+        Ok(Index {
+            postings,
+            docs,
+            term_trie,
+            avg_doc_length,
+        })
     }
 
     pub fn search<'a>(&'a self, query: &str) -> Vec<&'a Document> {
@@ -162,15 +451,186 @@ impl Index {
             .filter(|option| option.is_some())
             // Transform into just unique doc ids
             .flat_map(|option| option.unwrap())
+            .map(|posting| posting.doc_id)
             .unique()
             // Collect the actual documents
-            .map(|doc_id| &self.docs[*doc_id])
+            .map(|doc_id| &self.docs[doc_id])
+            .collect()
+    }
+
+    /// Parses `query` as a boolean expression (`AND`/`OR`/`NOT`, parentheses,
+    /// and `"phrase"` terms) and evaluates it against the postings list. See
+    /// the `query` module for the grammar.
+    pub fn search_query<'a>(&'a self, query: &str) -> Vec<&'a Document> {
+        let op = match query::parse(query) {
+            Some(op) => op,
+            None => return Vec::new(),
+        };
+
+        let mut doc_ids: Vec<usize> = self.eval(&op).into_iter().collect();
+        doc_ids.sort_unstable();
+        doc_ids.into_iter().map(|doc_id| &self.docs[doc_id]).collect()
+    }
+
+    /// Like `search`, but also matches terms within `max_distance` edits of
+    /// each query token (e.g. "recieve" finds docs indexed under "receive"),
+    /// via a bounded edit-distance walk of the term trie.
+    pub fn search_fuzzy<'a>(&'a self, query: &str, max_distance: usize) -> Vec<&'a Document> {
+        let doc_ids: HashSet<usize> = query
+            .to_lowercase()
+            .unicode_words()
+            .unique()
+            .flat_map(|tok| self.term_trie.fuzzy_matches(tok, max_distance))
+            .flat_map(|term| self.doc_ids_for_term(&term))
+            .collect();
+
+        let mut doc_ids: Vec<usize> = doc_ids.into_iter().collect();
+        doc_ids.sort_unstable();
+        doc_ids.into_iter().map(|doc_id| &self.docs[doc_id]).collect()
+    }
+
+    /// Like `search`, but scores each matching document with Okapi BM25 and
+    /// returns them sorted by descending relevance.
+    pub fn search_bm25<'a>(&'a self, query: &str) -> Vec<(&'a Document, f32)> {
+        let num_docs = self.docs.len() as f32;
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+
+        for term in query.to_lowercase().unicode_words().unique() {
+            let postings = match self.postings.get(term) {
+                Some(postings) => postings,
+                None => continue,
+            };
+
+            let df = postings.len() as f32;
+            let idf = ((num_docs - df + 0.5) / (df + 0.5) + 1.0).ln();
+
+            for posting in postings {
+                let tf = posting.positions.len() as f32;
+                let dl = self.docs[posting.doc_id].length as f32;
+                let norm = 1.0 - BM25_B + BM25_B * dl / self.avg_doc_length;
+                let score = idf * (tf * (BM25_K1 + 1.0)) / (tf + BM25_K1 * norm);
+                *scores.entry(posting.doc_id).or_insert(0.0) += score;
+            }
+        }
+
+        let mut scores: Vec<(usize, f32)> = scores.into_iter().collect();
+        scores.sort_unstable_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+
+        scores
+            .into_iter()
+            .map(|(doc_id, score)| (&self.docs[doc_id], score))
             .collect()
     }
+
+    fn eval(&self, op: &Op) -> HashSet<usize> {
+        match op {
+            Op::Term(term) => self.doc_ids_for_term(term),
+            Op::Phrase(terms) => self.doc_ids_for_phrase(terms),
+            Op::And(ops) => {
+                // The common case - ANDing plain terms together - leapfrogs
+                // the postings directly instead of materializing a HashSet
+                // per operand. Mixed ANDs (nesting Or/Not/Phrase) fall back
+                // to intersecting the evaluated operand sets.
+                if let Some(terms) = as_plain_terms(ops) {
+                    return self.intersect_terms(&terms).into_iter().collect();
+                }
+
+                let mut sets = ops.iter().map(|op| self.eval(op));
+                match sets.next() {
+                    Some(first) => sets.fold(first, |acc, set| acc.intersection(&set).copied().collect()),
+                    None => HashSet::new(),
+                }
+            }
+            Op::Or(ops) => ops.iter().fold(HashSet::new(), |mut acc, op| {
+                acc.extend(self.eval(op));
+                acc
+            }),
+            Op::Not(inner) => {
+                let excluded = self.eval(inner);
+                (0..self.docs.len())
+                    .filter(|doc_id| !excluded.contains(doc_id))
+                    .collect()
+            }
+        }
+    }
+
+    fn doc_ids_for_term(&self, term: &str) -> HashSet<usize> {
+        self.postings
+            .get(term)
+            .map(|postings| postings.iter().map(|posting| posting.doc_id).collect())
+            .unwrap_or_default()
+    }
+
+    /// Leapfrogs the postings for each term straight to their intersection.
+    /// A term with no postings at all means the whole AND is empty.
+    fn intersect_terms(&self, terms: &[&str]) -> Vec<usize> {
+        let sets: Option<Vec<PostingsDocSet>> = terms
+            .iter()
+            .map(|term| self.postings.get(*term).map(|postings| PostingsDocSet::new(postings)))
+            .collect();
+
+        match sets {
+            Some(sets) => leapfrog_intersect(sets),
+            None => Vec::new(),
+        }
+    }
+
+    fn doc_ids_for_phrase(&self, terms: &[String]) -> HashSet<usize> {
+        let first_term = match terms.first() {
+            Some(first_term) => first_term,
+            None => return HashSet::new(),
+        };
+
+        let candidates = match self.postings.get(first_term) {
+            Some(postings) => postings,
+            None => return HashSet::new(),
+        };
+
+        candidates
+            .iter()
+            .filter(|posting| self.phrase_matches_doc(terms, posting.doc_id))
+            .map(|posting| posting.doc_id)
+            .collect()
+    }
+
+    /// A phrase matches a doc if every term has a position list, and there's
+    /// some starting position where each subsequent term occurs exactly one
+    /// position later than the last.
+    fn phrase_matches_doc(&self, terms: &[String], doc_id: usize) -> bool {
+        let position_lists: Option<Vec<&Vec<usize>>> = terms
+            .iter()
+            .map(|term| {
+                self.postings
+                    .get(term)?
+                    .iter()
+                    .find(|posting| posting.doc_id == doc_id)
+                    .map(|posting| &posting.positions)
+            })
+            .collect();
+
+        let position_lists = match position_lists {
+            Some(position_lists) => position_lists,
+            None => return false,
+        };
+
+        position_lists[0].iter().any(|&start| {
+            position_lists
+                .iter()
+                .enumerate()
+                .all(|(offset, positions)| positions.contains(&(start + offset)))
+        })
+    }
 }
 
 #[derive(Debug, Snafu)]
 pub enum IndexWriterError {
+    UnableToWriteMagic {
+        source: io::Error,
+    },
+    UnableToWriteFormatVersion {
+        source: io::Error,
+    },
+
     UnableToDownCastPostingsLength {
         len: usize,
         source: core::num::TryFromIntError,
@@ -193,12 +653,12 @@ pub enum IndexWriterError {
         source: io::Error,
     },
 
-    UnableToDownCastNumberOfDocIds {
-        num_docs: usize,
+    UnableToDownCastNumberOfPostings {
+        num_postings: usize,
         source: core::num::TryFromIntError,
     },
-    UnableToWriteNumberOfDocIds {
-        num_docs: u32,
+    UnableToWriteNumberOfPostings {
+        num_postings: u32,
         source: io::Error,
     },
 
@@ -211,6 +671,23 @@ pub enum IndexWriterError {
         source: io::Error,
     },
 
+    UnableToDownCastNumberOfPositions {
+        num_positions: usize,
+        source: core::num::TryFromIntError,
+    },
+    UnableToWriteNumberOfPositions {
+        num_positions: u32,
+        source: io::Error,
+    },
+    UnableToDownCastPosition {
+        position: usize,
+        source: core::num::TryFromIntError,
+    },
+    UnableToWritePosition {
+        position: u32,
+        source: io::Error,
+    },
+
     UnableToDownCastNumberOfDocs {
         num_docs: usize,
         source: core::num::TryFromIntError,
@@ -220,6 +697,16 @@ pub enum IndexWriterError {
         source: io::Error,
     },
 
+    UnableToDownCastDocWordLength {
+        doc_id: usize,
+        length: usize,
+        source: core::num::TryFromIntError,
+    },
+    UnableToWriteDocWordLength {
+        doc_id: usize,
+        source: io::Error,
+    },
+
     UnableToDownCastDocLength {
         content_len: usize,
         source: core::num::TryFromIntError,
@@ -233,42 +720,211 @@ pub enum IndexWriterError {
         source: io::Error,
     },
 
+    UnableToDownCastNumberOfStoredFields {
+        doc_id: usize,
+        num_stored_fields: usize,
+        source: core::num::TryFromIntError,
+    },
+    UnableToWriteNumberOfStoredFields {
+        doc_id: usize,
+        source: io::Error,
+    },
+    UnableToDownCastStoredFieldNameLength {
+        doc_id: usize,
+        field: String,
+        len: usize,
+        source: core::num::TryFromIntError,
+    },
+    UnableToWriteStoredFieldName {
+        doc_id: usize,
+        field: String,
+        source: io::Error,
+    },
+    UnableToDownCastStoredFieldValueLength {
+        doc_id: usize,
+        field: String,
+        len: usize,
+        source: core::num::TryFromIntError,
+    },
+    UnableToWriteStoredFieldValue {
+        doc_id: usize,
+        field: String,
+        source: io::Error,
+    },
+
     UnableToFlush {
         source: io::Error,
     },
+
+    UnableToCreateTmpDir {
+        source: io::Error,
+    },
+    UnableToWriteSegment {
+        source: merge::MergeError,
+    },
+    UnableToCountMergedTerms {
+        source: merge::MergeError,
+    },
+    UnableToMergeSegments {
+        source: merge::MergeError,
+    },
+    UnableToWriteDocSegment {
+        source: docstore::DocStoreError,
+    },
+    UnableToMergeDocSegments {
+        source: docstore::DocStoreError,
+    },
 }
 
 #[derive(PartialEq, Debug, Default)]
 pub struct IndexWriter {
     docs: Vec<Document>,
     postings: PostingsList,
+    // Set via `with_memory_limit`; once estimated postings or doc memory
+    // exceeds this, the corresponding buffer is flushed to a segment file
+    // and cleared.
+    memory_limit: Option<usize>,
+    estimated_postings_bytes: usize,
+    estimated_docs_bytes: usize,
+    tmp_dir: Option<TmpDir>,
+    segments: Vec<PathBuf>,
+    doc_segments: Vec<PathBuf>,
+    // Doc ids must stay stable across a postings or doc flush, so they're
+    // assigned from this counter rather than `docs.len()`, which resets
+    // every time `docs` is flushed and cleared.
+    total_docs: usize,
 }
 
 impl From<IndexWriter> for Index {
-    fn from(writer: IndexWriter) -> Self {
+    fn from(mut writer: IndexWriter) -> Self {
+        if !writer.segments.is_empty() {
+            // Bring the spilled segments back in alongside whatever's still
+            // in memory, same as `write`/`read` would, so converting a
+            // spilling writer straight into an `Index` is equivalent to
+            // round-tripping it through disk.
+            let mut merged = merge::read_merged_postings(&writer.segments)
+                .expect("unable to read spilled segments");
+            for (term, postings) in writer.postings.drain() {
+                merged.entry(term).or_insert_with(Vec::new).extend(postings);
+            }
+            writer.postings = merged;
+        }
+
+        if !writer.doc_segments.is_empty() {
+            // Doc segments were flushed in doc-id order and whatever's
+            // still in `docs` is the tail after the last flush, so
+            // concatenating reconstructs the full, correctly ordered list.
+            let mut docs = docstore::read_merged_docs(&writer.doc_segments)
+                .expect("unable to read spilled doc segments");
+            docs.append(&mut writer.docs);
+            writer.docs = docs;
+        }
+
+        let term_trie = TermTrie::build(writer.postings.keys());
+        let avg_doc_length = avg_doc_length(&writer.docs);
+
         Index {
             docs: writer.docs,
             postings: writer.postings,
+            term_trie,
+            avg_doc_length,
         }
     }
 }
 
 impl IndexWriter {
-    pub fn add(&mut self, doc: AnalyzedDocument) {
-        let doc_id = self.docs.len();
-        for term in doc.terms {
-            (self
-                .postings
-                .entry(term.to_string())
-                .or_insert_with(Vec::new))
-            .push(doc_id);
+    /// Builds a writer that spills its postings, and separately its
+    /// documents, to temporary segment files once their estimated
+    /// in-memory size passes `bytes`, so indexing a corpus much larger
+    /// than RAM - where document content is typically the dominant cost,
+    /// e.g. a maildir's message bodies - doesn't exhaust it. Segments are
+    /// merged back together when `write` is called. See the `merge` and
+    /// `docstore` modules.
+    pub fn with_memory_limit(bytes: usize) -> Self {
+        IndexWriter {
+            memory_limit: Some(bytes),
+            ..IndexWriter::default()
+        }
+    }
+
+    pub fn add(&mut self, doc: AnalyzedDocument) -> Result<(), IndexWriterError> {
+        let doc_id = self.total_docs;
+        self.total_docs += 1;
+
+        for (term, positions) in doc.term_positions {
+            self.estimated_postings_bytes += estimated_posting_bytes(&term, &positions);
+            self.postings
+                .entry(term)
+                .or_insert_with(Vec::new)
+                .push(Posting { doc_id, positions });
         }
-        self.docs.push(Document {
+
+        let document = Document {
             content: doc.content.to_string(),
-        });
+            length: doc.length,
+            stored: doc.stored,
+        };
+        self.estimated_docs_bytes += estimated_doc_bytes(&document);
+        self.docs.push(document);
+
+        if let Some(limit) = self.memory_limit {
+            if self.estimated_postings_bytes > limit {
+                self.flush_postings_to_segment()?;
+            }
+            if self.estimated_docs_bytes > limit {
+                self.flush_docs_to_segment()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sorts the current postings by term and writes them to a new segment
+    /// file, then clears them from memory. A no-op if there's nothing to
+    /// flush (e.g. `write` calling this right after the last `add` already
+    /// flushed).
+    fn flush_postings_to_segment(&mut self) -> Result<(), IndexWriterError> {
+        if self.postings.is_empty() {
+            return Ok(());
+        }
+
+        if self.tmp_dir.is_none() {
+            self.tmp_dir = Some(TmpDir::create().context(UnableToCreateTmpDir)?);
+        }
+        let path = self.tmp_dir.as_mut().unwrap().next_segment_path();
+
+        merge::write_segment(&path, &self.postings).context(UnableToWriteSegment)?;
+
+        self.segments.push(path);
+        self.postings.clear();
+        self.estimated_postings_bytes = 0;
+
+        Ok(())
+    }
+
+    /// Writes the current documents, in order, to a new segment file, then
+    /// clears them from memory. A no-op if there's nothing to flush (e.g.
+    /// `write` calling this right after the last `add` already flushed).
+    fn flush_docs_to_segment(&mut self) -> Result<(), IndexWriterError> {
+        if self.docs.is_empty() {
+            return Ok(());
+        }
+
+        if self.tmp_dir.is_none() {
+            self.tmp_dir = Some(TmpDir::create().context(UnableToCreateTmpDir)?);
+        }
+        let path = self.tmp_dir.as_mut().unwrap().next_segment_path();
+
+        docstore::write_segment(&path, &self.docs).context(UnableToWriteDocSegment)?;
+
+        self.doc_segments.push(path);
+        self.docs.clear();
+        self.estimated_docs_bytes = 0;
+
+        Ok(())
     }
 
-    pub fn write<W>(&self, writer: W) -> Result<(), IndexWriterError>
+    pub fn write<W>(&mut self, writer: W) -> Result<(), IndexWriterError>
     where
         W: io::Write,
     {
@@ -283,58 +939,142 @@ impl IndexWriter {
         //
         // Format:
         //
-        // POSTINGS_SIZE:u32 [TERM_SIZE:u8 TERM NUM_DOC_IDS: u32 [u32, u32]], ...
-        let postings_len = self.postings.keys().len();
-        let postings_len = u32::try_from(postings_len)
-            .context(UnableToDownCastPostingsLength { len: postings_len })?;
+        // MAGIC:u32 VERSION:u32
+        // POSTINGS_SIZE:u32 [TERM_SIZE:u16 TERM NUM_POSTINGS:u32 [DOC_ID_GAP:varint NUM_POSITIONS:u32 [POSITION:u32, ...], ...], ...], ...
+        // NUM_DOCS:u32 [DOC_WORD_LENGTH:u32 CONTENT_SIZE:u32 CONTENT NUM_STORED:u32 [FIELD_NAME_SIZE:u16 FIELD_NAME FIELD_VALUE_SIZE:u32 FIELD_VALUE, ...], ...]
+        //
+        // DOC_ID_GAP is the gap from the previous doc id in this term's
+        // postings (or from 0 for the first), variable-byte encoded: 7 bits
+        // of value per byte, high bit set as a continuation flag. Doc ids
+        // only increase within a term's postings, so gaps (and therefore
+        // their varint encoding) are always small relative to the raw id.
         writer
-            .write_all(&postings_len.to_be_bytes())
-            .context(UnableToWritePostingsLength)?;
-
-        for (term, doc_ids) in &self.postings {
-            // Term length, then term
-            let term_bytes = term.as_bytes();
-            let term_length = term_bytes.len();
-            let term_length = u16::try_from(term_length).context(UnableToDownCastTermLength {
-                term: term,
-                len: term_length,
-            })?;
-
-            writer
-                .write_all(&term_length.to_be_bytes())
-                .context(UnableToWriteTermLength { term: term })?;
+            .write_all(&FORMAT_MAGIC.to_be_bytes())
+            .context(UnableToWriteMagic)?;
+        writer
+            .write_all(&FORMAT_VERSION.to_be_bytes())
+            .context(UnableToWriteFormatVersion)?;
+
+        if self.segments.is_empty() {
+            // Nothing was ever spilled to disk - the common case for a
+            // corpus that comfortably fits in memory. Write postings
+            // straight out of the map, same as before segments existed.
+            let postings_len = self.postings.keys().len();
+            let postings_len = u32::try_from(postings_len)
+                .context(UnableToDownCastPostingsLength { len: postings_len })?;
             writer
-                .write_all(&term_bytes[..])
-                .context(UnableToWriteTerm { term: term })?;
+                .write_all(&postings_len.to_be_bytes())
+                .context(UnableToWritePostingsLength)?;
+
+            for (term, postings) in &self.postings {
+                // Term length, then term
+                let term_bytes = term.as_bytes();
+                let term_length = term_bytes.len();
+                let term_length = u16::try_from(term_length).context(UnableToDownCastTermLength {
+                    term: term,
+                    len: term_length,
+                })?;
 
-            // Number of docs, then the docs
-            let num_docs = doc_ids.len();
-            let num_docs =
-                u32::try_from(num_docs).context(UnableToDownCastNumberOfDocIds { num_docs })?;
-            writer
-                .write_all(&num_docs.to_be_bytes())
-                .context(UnableToWriteNumberOfDocIds { num_docs })?;
+                writer
+                    .write_all(&term_length.to_be_bytes())
+                    .context(UnableToWriteTermLength { term: term })?;
+                writer
+                    .write_all(&term_bytes[..])
+                    .context(UnableToWriteTerm { term: term })?;
 
-            for doc_id in doc_ids {
-                let doc_id =
-                    u32::try_from(*doc_id).context(UnableToDownCastDocId { doc_id: *doc_id })?;
+                // Number of postings, then the postings
+                let num_postings = postings.len();
+                let num_postings = u32::try_from(num_postings)
+                    .context(UnableToDownCastNumberOfPostings { num_postings })?;
                 writer
-                    .write_all(&doc_id.to_be_bytes())
-                    .context(UnableToWriteDocId { doc_id })?;
+                    .write_all(&num_postings.to_be_bytes())
+                    .context(UnableToWriteNumberOfPostings { num_postings })?;
+
+                // Doc ids are ascending within a term's postings, so each is
+                // written as the gap from the previous one (the first gap is
+                // from 0), varint-encoded.
+                let mut prev_doc_id: u32 = 0;
+                for posting in postings {
+                    let doc_id = u32::try_from(posting.doc_id)
+                        .context(UnableToDownCastDocId { doc_id: posting.doc_id })?;
+                    write_varint(&mut writer, doc_id - prev_doc_id)
+                        .context(UnableToWriteDocId { doc_id })?;
+                    prev_doc_id = doc_id;
+
+                    let num_positions = posting.positions.len();
+                    let num_positions = u32::try_from(num_positions)
+                        .context(UnableToDownCastNumberOfPositions { num_positions })?;
+                    writer
+                        .write_all(&num_positions.to_be_bytes())
+                        .context(UnableToWriteNumberOfPositions { num_positions })?;
+
+                    for position in &posting.positions {
+                        let position = u32::try_from(*position)
+                            .context(UnableToDownCastPosition { position: *position })?;
+                        writer
+                            .write_all(&position.to_be_bytes())
+                            .context(UnableToWritePosition { position })?;
+                    }
+                }
             }
+        } else {
+            // Flush whatever's still in memory so it joins the merge as one
+            // more segment, then stream the k-way merge of every segment
+            // straight into the output - at most one term's postings are
+            // ever held in memory at a time.
+            self.flush_postings_to_segment()?;
+
+            let postings_len =
+                merge::count_merged_terms(&self.segments).context(UnableToCountMergedTerms)?;
+            writer
+                .write_all(&postings_len.to_be_bytes())
+                .context(UnableToWritePostingsLength)?;
+
+            merge::write_merged_postings(&self.segments, &mut writer)
+                .context(UnableToMergeSegments)?;
         }
 
         // Write documents
         //
         // Number of documents, then doc length and content pairs
-        let num_docs = self.docs.len();
+        let num_docs = self.total_docs;
         let num_docs =
             u32::try_from(num_docs).context(UnableToDownCastNumberOfDocs { num_docs })?;
         writer
             .write_all(&num_docs.to_be_bytes())
             .context(UnableToWriteNumberOfDocs { num_docs })?;
 
-        for doc in &self.docs {
+        if self.doc_segments.is_empty() {
+            // Nothing was ever spilled to disk - the common case for a
+            // corpus that comfortably fits in memory. Write docs straight
+            // out of the vec, same as before doc segments existed.
+            self.write_docs_inline(&mut writer)?;
+        } else {
+            // Flush whatever's still in memory so it joins the
+            // concatenation as one more segment, then stream every doc
+            // segment straight into the output in flush (i.e. doc-id)
+            // order - at most one document is ever held in memory at once.
+            self.flush_docs_to_segment()?;
+
+            docstore::write_merged_docs(&self.doc_segments, &mut writer)
+                .context(UnableToMergeDocSegments)?;
+        }
+
+        writer.flush().context(UnableToFlush)?;
+
+        Ok(())
+    }
+
+    fn write_docs_inline<W: io::Write>(&self, writer: &mut W) -> Result<(), IndexWriterError> {
+        for (doc_id, doc) in self.docs.iter().enumerate() {
+            let word_length = u32::try_from(doc.length).context(UnableToDownCastDocWordLength {
+                doc_id,
+                length: doc.length,
+            })?;
+            writer
+                .write_all(&word_length.to_be_bytes())
+                .context(UnableToWriteDocWordLength { doc_id })?;
+
             let content_len = doc.content.len();
             let content_len =
                 u32::try_from(content_len).context(UnableToDownCastDocLength { content_len })?;
@@ -344,9 +1084,45 @@ impl IndexWriter {
             writer
                 .write_all(doc.content.as_bytes())
                 .context(UnableToWriteDoc)?;
-        }
 
-        writer.flush().context(UnableToFlush)?;
+            let num_stored_fields = doc.stored.len();
+            let num_stored_fields_u32 = u32::try_from(num_stored_fields)
+                .context(UnableToDownCastNumberOfStoredFields { doc_id, num_stored_fields })?;
+            writer
+                .write_all(&num_stored_fields_u32.to_be_bytes())
+                .context(UnableToWriteNumberOfStoredFields { doc_id })?;
+
+            for (field, value) in &doc.stored {
+                let field_bytes = field.as_bytes();
+                let field_length =
+                    u16::try_from(field_bytes.len()).context(UnableToDownCastStoredFieldNameLength {
+                        doc_id,
+                        field: field.clone(),
+                        len: field_bytes.len(),
+                    })?;
+                writer
+                    .write_all(&field_length.to_be_bytes())
+                    .context(UnableToWriteStoredFieldName { doc_id, field: field.clone() })?;
+                writer
+                    .write_all(field_bytes)
+                    .context(UnableToWriteStoredFieldName { doc_id, field: field.clone() })?;
+
+                let value_bytes = value.as_bytes();
+                let value_length = u32::try_from(value_bytes.len()).context(
+                    UnableToDownCastStoredFieldValueLength {
+                        doc_id,
+                        field: field.clone(),
+                        len: value_bytes.len(),
+                    },
+                )?;
+                writer
+                    .write_all(&value_length.to_be_bytes())
+                    .context(UnableToWriteStoredFieldValue { doc_id, field: field.clone() })?;
+                writer
+                    .write_all(value_bytes)
+                    .context(UnableToWriteStoredFieldValue { doc_id, field: field.clone() })?;
+            }
+        }
 
         Ok(())
     }
@@ -362,11 +1138,23 @@ mod tests {
     fn add_to_index() {
         let mut idx = IndexWriter::default();
 
-        idx.add(analyze("hello".to_string()));
-        idx.add(analyze("world".to_string()));
+        idx.add(analyze("hello".to_string())).unwrap();
+        idx.add(analyze("world".to_string())).unwrap();
 
-        assert_eq!(Some(&vec![0]), idx.postings.get("hello"));
-        assert_eq!(Some(&vec![1]), idx.postings.get("world"));
+        assert_eq!(
+            Some(&vec![Posting {
+                doc_id: 0,
+                positions: vec![0]
+            }]),
+            idx.postings.get("hello")
+        );
+        assert_eq!(
+            Some(&vec![Posting {
+                doc_id: 1,
+                positions: vec![0]
+            }]),
+            idx.postings.get("world")
+        );
     }
 
     #[test]
@@ -377,9 +1165,9 @@ mod tests {
 
         let expected = vec![dogs.content.clone(), cats_better.content.clone()];
 
-        idx.add(dogs);
-        idx.add(cats_better);
-        idx.add(analyze("no".to_string()));
+        idx.add(dogs).unwrap();
+        idx.add(cats_better).unwrap();
+        idx.add(analyze("no".to_string())).unwrap();
 
         let results: Vec<String> = Index::from(idx)
             .search("cats")
@@ -396,8 +1184,11 @@ mod tests {
         let idx = IndexWriter::default();
         idx.write(&mut buf)?;
 
-        // No postings (4 bytes) no docs (4 bytes)
-        assert_eq!(&[0; 8], &buf.get_ref()[..]);
+        // Magic (4 bytes) version (4 bytes) no postings (4 bytes) no docs (4 bytes)
+        assert_eq!(
+            &[0x52, 0x53, 0x44, 0x58, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0],
+            &buf.get_ref()[..]
+        );
 
         Ok(())
     }
@@ -407,20 +1198,26 @@ mod tests {
         // We create an index with a postings list but no docs
         // for test purposes only. This shouldn't really exist in practice.
         let mut index = IndexWriter::default();
-        index.add(analyze("foo".to_string()));
+        index.add(analyze("foo".to_string())).unwrap();
 
         let mut buf = io::Cursor::new(vec![]);
         index.write(&mut buf)?;
 
         assert_eq!(
             &[
+                0x52, 0x53, 0x44, 0x58, // Magic
+                0, 0, 0, 3, // Format version
                 0, 0, 0, 1, // One posting
                 0, 3, // Three letters
-                b'f', b'o', b'o', 0, 0, 0, 1, // One doc_id
-                0, 0, 0, 0, // Doc 0
+                b'f', b'o', b'o', 0, 0, 0, 1, // One posting for this term
+                0, // Doc 0, as a single-byte varint gap from 0
+                0, 0, 0, 1, // One position
+                0, 0, 0, 0, // Position 0
                 0, 0, 0, 1, // One doc
-                0, 0, 0, 3, // Length of first doc
-                b'f', b'o', b'o' // The doc content
+                0, 0, 0, 1, // Doc word length
+                0, 0, 0, 3, // Length of first doc's content
+                b'f', b'o', b'o', // The doc content
+                0, 0, 0, 0 // No stored fields
             ],
             &buf.get_ref()[..]
         );
@@ -431,24 +1228,276 @@ mod tests {
     #[test]
     fn read_with_one_doc_and_term() -> Result<(), IndexError> {
         let buf =
+            // Magic and format version
+            [0x52, 0x53, 0x44, 0x58, 0, 0, 0, 3,
             // One term in the postings list: foo
-            [0, 0, 0, 1,
+                0, 0, 0, 1,
                 0, 3,
                 b'f', b'o', b'o',
-            // To one doc, doc_id 0
-                0, 0, 0, 1, 0, 0, 0, 0,
-            // One stored doc, of length 3
+            // One posting, doc_id 0 (a single-byte varint gap from 0), at position 0
+                0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0,
+            // One stored doc
              0, 0, 0, 1,
-            // Doc length 3
+            // Doc word length 1, content length 3
+                0, 0, 0, 1,
                 0, 0, 0, 3,
             // And the doc content
-                b'f', b'o', b'o'];
+                b'f', b'o', b'o',
+            // No stored fields
+                0, 0, 0, 0];
         let index = Index::read(io::Cursor::new(&buf))?;
         let mut expected_index = IndexWriter::default();
-        expected_index.add(analyze("foo".to_string()));
+        expected_index.add(analyze("foo".to_string())).unwrap();
         let expected_index = Index::from(expected_index);
 
         assert_eq!(expected_index, index);
         Ok(())
     }
+
+    // search_query tests
+
+    fn query_index() -> Index {
+        let mut idx = IndexWriter::default();
+        idx.add(analyze("cats and dogs are friends".to_string()))
+            .unwrap(); // 0
+        idx.add(analyze("birds sing in the morning".to_string()))
+            .unwrap(); // 1
+        idx.add(analyze("snakes eat birds sometimes".to_string()))
+            .unwrap(); // 2
+        idx.add(analyze("exactly this phrase appears here".to_string()))
+            .unwrap(); // 3
+        Index::from(idx)
+    }
+
+    fn contents(docs: Vec<&Document>) -> Vec<String> {
+        docs.into_iter().map(|d| d.content.clone()).collect()
+    }
+
+    #[test]
+    fn search_query_and() {
+        let index = query_index();
+        assert_eq!(
+            vec!["cats and dogs are friends".to_string()],
+            contents(index.search_query("cats AND dogs"))
+        );
+    }
+
+    #[test]
+    fn search_query_and_leapfrogs_three_terms() {
+        let index = query_index();
+        assert_eq!(
+            vec!["cats and dogs are friends".to_string()],
+            contents(index.search_query("cats AND dogs AND friends"))
+        );
+        assert!(contents(index.search_query("cats AND dogs AND birds")).is_empty());
+    }
+
+    #[test]
+    fn search_query_or() {
+        let index = query_index();
+        let results = contents(index.search_query("cats OR birds"));
+        assert_eq!(3, results.len());
+    }
+
+    #[test]
+    fn search_query_not() {
+        let index = query_index();
+        let results = contents(index.search_query("birds NOT snakes"));
+        assert_eq!(vec!["birds sing in the morning".to_string()], results);
+    }
+
+    #[test]
+    fn search_query_grouping() {
+        let index = query_index();
+        let results = contents(index.search_query("cats AND (dogs OR birds)"));
+        assert_eq!(vec!["cats and dogs are friends".to_string()], results);
+    }
+
+    #[test]
+    fn search_query_grouping_with_trailing_bare_not() {
+        let index = query_index();
+        let results = contents(index.search_query("cats AND (dogs OR birds) NOT snakes"));
+        assert_eq!(vec!["cats and dogs are friends".to_string()], results);
+    }
+
+    #[test]
+    fn search_query_phrase() {
+        let index = query_index();
+        let results = contents(index.search_query("\"exactly this phrase\""));
+        assert_eq!(
+            vec!["exactly this phrase appears here".to_string()],
+            results
+        );
+
+        let no_match = contents(index.search_query("\"this exactly phrase\""));
+        assert!(no_match.is_empty());
+    }
+
+    // search_fuzzy tests
+
+    #[test]
+    fn search_fuzzy_finds_typo() {
+        let mut idx = IndexWriter::default();
+        idx.add(analyze("please receive this package".to_string()))
+            .unwrap();
+        let index = Index::from(idx);
+
+        let results = contents(index.search_fuzzy("recieve", 2));
+        assert_eq!(vec!["please receive this package".to_string()], results);
+    }
+
+    #[test]
+    fn search_fuzzy_respects_max_distance() {
+        let mut idx = IndexWriter::default();
+        idx.add(analyze("please receive this package".to_string()))
+            .unwrap();
+        let index = Index::from(idx);
+
+        assert!(index.search_fuzzy("recieve", 0).is_empty());
+    }
+
+    // search_bm25 tests
+
+    #[test]
+    fn search_bm25_ranks_by_relevance() {
+        let mut idx = IndexWriter::default();
+        idx.add(analyze("cats cats cats are great".to_string()))
+            .unwrap(); // high term frequency
+        idx.add(analyze("cats are fine I suppose".to_string()))
+            .unwrap(); // low term frequency
+        let index = Index::from(idx);
+
+        let results = index.search_bm25("cats");
+        assert_eq!(2, results.len());
+        assert_eq!("cats cats cats are great", results[0].0.content);
+        assert!(results[0].1 > results[1].1);
+    }
+
+    #[test]
+    fn search_bm25_ignores_nonmatching_docs() {
+        let mut idx = IndexWriter::default();
+        idx.add(analyze("cats are great".to_string())).unwrap();
+        idx.add(analyze("dogs are great".to_string())).unwrap();
+        let index = Index::from(idx);
+
+        let results = index.search_bm25("cats");
+        assert_eq!(1, results.len());
+        assert_eq!("cats are great", results[0].0.content);
+    }
+
+    #[test]
+    fn read_rejects_unsupported_format_version() {
+        let buf = [0x52, 0x53, 0x44, 0x58, 0, 0, 0, 99];
+        let err = Index::read(io::Cursor::new(&buf)).unwrap_err();
+        assert!(matches!(err, IndexError::UnsupportedFormatVersion { found: 99 }));
+    }
+
+    // with_memory_limit / spill-to-disk tests
+
+    fn docs_for_spill_test() -> Vec<String> {
+        vec![
+            "cats and dogs are friends".to_string(),
+            "birds sing in the morning".to_string(),
+            "snakes eat birds sometimes".to_string(),
+            "exactly this phrase appears here".to_string(),
+            "dogs chase cats around the yard".to_string(),
+        ]
+    }
+
+    #[test]
+    fn with_memory_limit_spills_to_multiple_segments() {
+        let mut idx = IndexWriter::with_memory_limit(1);
+        for content in docs_for_spill_test() {
+            idx.add(analyze(content)).unwrap();
+        }
+
+        assert!(idx.segments.len() > 1);
+    }
+
+    #[test]
+    fn with_memory_limit_spills_doc_content_to_multiple_segments() {
+        let mut idx = IndexWriter::with_memory_limit(1);
+        for content in docs_for_spill_test() {
+            idx.add(analyze(content)).unwrap();
+        }
+
+        assert!(idx.doc_segments.len() > 1);
+    }
+
+    #[test]
+    fn with_memory_limit_matches_unlimited_index_when_written_and_read() {
+        let mut limited = IndexWriter::with_memory_limit(1);
+        let mut unlimited = IndexWriter::default();
+        for content in docs_for_spill_test() {
+            limited.add(analyze(content.clone())).unwrap();
+            unlimited.add(analyze(content)).unwrap();
+        }
+
+        let mut limited_buf = io::Cursor::new(vec![]);
+        limited.write(&mut limited_buf).unwrap();
+
+        let mut unlimited_buf = io::Cursor::new(vec![]);
+        unlimited.write(&mut unlimited_buf).unwrap();
+
+        let limited_index = Index::read(io::Cursor::new(limited_buf.get_ref())).unwrap();
+        let unlimited_index = Index::read(io::Cursor::new(unlimited_buf.get_ref())).unwrap();
+
+        assert_eq!(unlimited_index, limited_index);
+    }
+
+    #[test]
+    fn with_memory_limit_search_results_match_unlimited() {
+        let mut limited = IndexWriter::with_memory_limit(1);
+        for content in docs_for_spill_test() {
+            limited.add(analyze(content)).unwrap();
+        }
+        let index = Index::from(limited);
+
+        assert_eq!(
+            vec!["cats and dogs are friends".to_string()],
+            contents(index.search_query("cats AND dogs"))
+        );
+    }
+
+    // analyze_record tests
+
+    fn record(fields: &[(&str, &str)]) -> source::Record {
+        source::Record {
+            identifier: "test".to_string(),
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn analyze_record_indexes_only_searchable_fields() {
+        let rec = record(&[("title", "cats"), ("body", "dogs are friends")]);
+        let searchable = vec!["body".to_string()];
+        let stored = Vec::new();
+
+        let analyzed = analyze_record(rec, &searchable, &stored);
+        assert_eq!("dogs are friends", analyzed.content);
+    }
+
+    #[test]
+    fn analyze_record_carries_stored_fields_through_to_document() {
+        let rec = record(&[("title", "Cats and Dogs"), ("body", "dogs are friends")]);
+        let searchable = vec!["body".to_string()];
+        let stored = vec!["title".to_string()];
+
+        let mut idx = IndexWriter::default();
+        idx.add(analyze_record(rec, &searchable, &stored)).unwrap();
+        let index = Index::from(idx);
+
+        assert_eq!(
+            Some(&"Cats and Dogs".to_string()),
+            index.docs[0].stored.get("title")
+        );
+        assert_eq!(
+            vec!["dogs are friends".to_string()],
+            contents(index.search_query("friends"))
+        );
+    }
 }
@@ -1,27 +1,23 @@
 use std::{
     fs,
-    path::{Path, PathBuf},
     time::Instant,
     thread,
     sync::{Arc, Mutex, mpsc::channel}
 };
 
-use rsearch::{Index, IndexWriter};
+use rsearch::{
+    source::{CsvSource, DocumentSource, JsonlSource, MailSource, Record},
+    analyze_record, Index, IndexWriter,
+};
 
 use clap::{crate_authors, crate_description, crate_name, crate_version, App, SubCommand, Arg};
-use mailparse;
-use walkdir::{DirEntry, WalkDir};
-
-fn is_hidden(entry: &DirEntry) -> bool {
-    entry.file_name()
-         .to_str()
-         .map(|s| s.starts_with('.'))
-         .unwrap_or(false)
-}
 
-fn mail_content(path: &Path) -> Result<String, mailparse::MailParseError> {
-    let content = fs::read(path).unwrap();
-    Ok(mailparse::parse_mail(&content)?.get_body()?.trim().to_string())
+fn split_fields(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|field| field.trim().to_string())
+        .filter(|field| !field.is_empty())
+        .collect()
 }
 
 fn main() -> std::result::Result<(), std::io::Error> {
@@ -31,19 +27,37 @@ fn main() -> std::result::Result<(), std::io::Error> {
         .author(crate_authors!())
         //.subcommand(SubCommand::with_name("query")
         .subcommand(SubCommand::with_name("create")
-                        .arg(Arg::with_name("input_dir")
-                             .help("The directory to walk to find mail dirs")
+                        .arg(Arg::with_name("input")
+                             .help("The mail directory (--format mail) or file (--format csv/jsonl) to read")
                              .required(true))
                         .arg(Arg::with_name("output_file")
                              .help("The name of the index file")
-                             .required(true)))
+                             .required(true))
+                        .arg(Arg::with_name("format")
+                             .long("format")
+                             .takes_value(true)
+                             .possible_values(&["csv", "jsonl", "mail"])
+                             .default_value("mail")
+                             .help("The format of the input"))
+                        .arg(Arg::with_name("searchable")
+                             .long("searchable")
+                             .takes_value(true)
+                             .help("Comma-separated field names to tokenize and index (required for csv/jsonl)"))
+                        .arg(Arg::with_name("stored")
+                             .long("stored")
+                             .takes_value(true)
+                             .help("Comma-separated field names to carry through for display, without indexing them")))
         .subcommand(SubCommand::with_name("search")
                         .arg(Arg::with_name("index_file")
                              .help("The name of the index file")
                              .required(true))
                         .arg(Arg::with_name("query")
                              .help("What to search for")
-                             .required(true)))
+                             .required(true))
+                        .arg(Arg::with_name("display")
+                             .long("display")
+                             .takes_value(true)
+                             .help("Stored field to print for each match, instead of the indexed content")))
     .get_matches();
 
     if let Some(matches) = matches.subcommand_matches("search") {
@@ -51,14 +65,32 @@ fn main() -> std::result::Result<(), std::io::Error> {
         let index_file = matches.value_of("index_file").expect("index_file required");
         let index_file = fs::File::open(index_file).expect("Unable to open index file");
         let index = Index::read(index_file).expect("Unable to read index");
+        let display = matches.value_of("display");
 
         for doc in index.search(&query) {
-            println!("{}", doc.content);
+            match display.and_then(|field| doc.stored.get(field)) {
+                Some(value) => println!("{}", value),
+                None => println!("{}", doc.content),
+            }
         }
     } else if let Some(matches) = matches.subcommand_matches("create") {
-        let input_dir = matches.value_of("input_dir").expect("input_dir required");
+        let input = matches.value_of("input").expect("input required");
         let output_file = matches.value_of("output_file").expect("output_file required");
         let output_file = fs::File::create(output_file).expect("Unable to open output file");
+        let format = matches.value_of("format").expect("format has a default");
+        let searchable = matches.value_of("searchable").map(split_fields).unwrap_or_default();
+        let stored = matches.value_of("stored").map(split_fields).unwrap_or_default();
+
+        if format != "mail" && searchable.is_empty() {
+            panic!("--searchable is required when --format is csv or jsonl (otherwise every document indexes as empty content)");
+        }
+
+        let source: Box<dyn DocumentSource> = match format {
+            "csv" => Box::new(CsvSource::new(input)),
+            "jsonl" => Box::new(JsonlSource::new(input)),
+            "mail" => Box::new(MailSource::new(input)),
+            _ => unreachable!("clap restricts format to known values"),
+        };
 
         let mut index = IndexWriter::default();
 
@@ -66,24 +98,21 @@ fn main() -> std::result::Result<(), std::io::Error> {
 
         let (sender, receiver) = channel();
 
-        let walker = WalkDir::new(input_dir).into_iter();
-        let paths: Vec<PathBuf> = walker.filter_entry(|e| !is_hidden(e))
-                            .filter(|e| !e.as_ref().expect("Path entry in filter blew up").file_type().is_dir())
-                            .map(|e| PathBuf::from(e.expect("Path entry in map blew up").path()))
-                            .collect();
-        let paths = Arc::new(Mutex::new(paths));
+        let records: Vec<Record> = source
+            .records()
+            .map(|record| record.expect("Unable to read record from document source"))
+            .collect();
+        let records = Arc::new(Mutex::new(records));
 
-        println!("Paths collected at {:?}", start.elapsed());
+        println!("Records collected at {:?}", start.elapsed());
 
         let mut handles: Vec<thread::JoinHandle<_>> = Vec::new();
         for _ in 0..20 {
-            let (paths, tx) = (Arc::clone(&paths), sender.clone());
+            let (records, tx) = (Arc::clone(&records), sender.clone());
+            let (searchable, stored) = (searchable.clone(), stored.clone());
             handles.push(thread::spawn(move || {
-                while let Some(path) = { let x = (*paths.lock().expect("Mutex blew up")).pop(); x } {
-                    if let Ok(content) = mail_content(&path.as_path()) {
-                        let analyzed = rsearch::analyze(content);
-                        tx.send(analyzed).expect("Send failed");
-                    }
+                while let Some(record) = { let x = (*records.lock().expect("Mutex blew up")).pop(); x } {
+                    tx.send(analyze_record(record, &searchable, &stored)).expect("Send failed");
                 }
             }));
         }
@@ -94,10 +123,10 @@ fn main() -> std::result::Result<(), std::io::Error> {
 
         drop(sender);
 
-        println!("Done parsing at {:?}", start.elapsed());
+        println!("Done analyzing at {:?}", start.elapsed());
 
         while let Ok(analyzed) = receiver.recv() {
-            index.add(analyzed);
+            index.add(analyzed).expect("Unable to add document to index");
         }
 
         println!("Done reading at {:?}", start.elapsed());
@@ -0,0 +1,255 @@
+//! Boolean query parsing.
+//!
+//! Turns a raw query string into an `Op` tree that `Index::search_query` can
+//! evaluate against the postings list. Grammar, loosest to tightest binding:
+//!
+//! ```text
+//! or_expr  := and_expr (("OR")? and_expr)*   // bare juxtaposition defaults to OR
+//! and_expr := not_expr ("AND" not_expr)*
+//! not_expr := "NOT" not_expr | primary
+//! primary  := "(" or_expr ")" | phrase | term
+//! ```
+//!
+//! One exception to the default OR: a bare (non-`OR`-joined) `NOT` clause is
+//! ANDed with the clause before it, so `birds NOT snakes` means
+//! `birds AND NOT snakes`.
+//!
+//! `AND`, `OR`, and `NOT` are recognized only in uppercase, so they never
+//! collide with a lowercased search term.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Term(String),
+    Phrase(Vec<String>),
+    And(Vec<Op>),
+    Or(Vec<Op>),
+    Not(Box<Op>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Term(String),
+    Phrase(Vec<String>),
+}
+
+fn tokenize(query: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' {
+            chars.next();
+            tokens.push(Token::LParen);
+        } else if c == ')' {
+            chars.next();
+            tokens.push(Token::RParen);
+        } else if c == '"' {
+            chars.next();
+            let mut phrase = String::new();
+            while let Some(&c) = chars.peek() {
+                chars.next();
+                if c == '"' {
+                    break;
+                }
+                phrase.push(c);
+            }
+            let terms = phrase.unicode_words().map(|w| w.to_lowercase()).collect();
+            tokens.push(Token::Phrase(terms));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(match word.as_str() {
+                "AND" => Token::And,
+                "OR" => Token::Or,
+                "NOT" => Token::Not,
+                _ => Token::Term(word.to_lowercase()),
+            });
+        }
+    }
+
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Parser { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn advance(&mut self) -> Option<&Token> {
+        let tok = self.tokens.get(self.pos);
+        self.pos += 1;
+        tok
+    }
+
+    fn parse_or(&mut self) -> Option<Op> {
+        let mut terms = vec![self.parse_and()?];
+        loop {
+            match self.peek() {
+                Some(Token::Or) => {
+                    self.advance();
+                    terms.push(self.parse_and()?);
+                }
+                Some(Token::RParen) | None => break,
+                Some(Token::Not) => {
+                    // A bare (non-"OR"-joined) NOT clause excludes from the
+                    // preceding term rather than joining the OR chain, so
+                    // "cats AND (dogs OR birds) NOT snakes" means "... AND
+                    // NOT snakes", not "... OR NOT snakes".
+                    let not_clause = self.parse_and()?;
+                    let last = terms.pop().expect("terms starts non-empty");
+                    terms.push(Op::And(vec![last, not_clause]));
+                }
+                _ => terms.push(self.parse_and()?), // implicit OR between bare terms
+            }
+        }
+        Some(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            Op::Or(terms)
+        })
+    }
+
+    fn parse_and(&mut self) -> Option<Op> {
+        let mut terms = vec![self.parse_not()?];
+        while let Some(Token::And) = self.peek() {
+            self.advance();
+            terms.push(self.parse_not()?);
+        }
+        Some(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            Op::And(terms)
+        })
+    }
+
+    fn parse_not(&mut self) -> Option<Op> {
+        if let Some(Token::Not) = self.peek() {
+            self.advance();
+            return Some(Op::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Option<Op> {
+        match self.advance()? {
+            Token::LParen => {
+                let inner = self.parse_or()?;
+                if let Some(Token::RParen) = self.peek() {
+                    self.advance();
+                }
+                Some(inner)
+            }
+            Token::Term(term) => Some(Op::Term(term.clone())),
+            Token::Phrase(terms) => Some(Op::Phrase(terms.clone())),
+            Token::RParen | Token::And | Token::Or | Token::Not => None,
+        }
+    }
+}
+
+/// Parses a query string into an `Op` tree, or `None` if it contains no terms
+/// or an operator is missing its operand (e.g. a trailing `AND`).
+pub fn parse(query: &str) -> Option<Op> {
+    let tokens = tokenize(query);
+    if tokens.is_empty() {
+        return None;
+    }
+
+    Parser::new(&tokens).parse_or()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_term() {
+        assert_eq!(Some(Op::Term("cats".to_string())), parse("cats"));
+    }
+
+    #[test]
+    fn parses_implicit_or() {
+        assert_eq!(
+            Some(Op::Or(vec![
+                Op::Term("cats".to_string()),
+                Op::Term("dogs".to_string())
+            ])),
+            parse("cats dogs")
+        );
+    }
+
+    #[test]
+    fn parses_and() {
+        assert_eq!(
+            Some(Op::And(vec![
+                Op::Term("cats".to_string()),
+                Op::Term("dogs".to_string())
+            ])),
+            parse("cats AND dogs")
+        );
+    }
+
+    #[test]
+    fn parses_not() {
+        assert_eq!(
+            Some(Op::Not(Box::new(Op::Term("snakes".to_string())))),
+            parse("NOT snakes")
+        );
+    }
+
+    #[test]
+    fn parses_phrase() {
+        assert_eq!(
+            Some(Op::Phrase(vec!["exactly".to_string(), "this".to_string()])),
+            parse("\"exactly this\"")
+        );
+    }
+
+    #[test]
+    fn parses_parens_and_precedence() {
+        assert_eq!(
+            Some(Op::And(vec![
+                Op::Term("cats".to_string()),
+                Op::Or(vec![Op::Term("dogs".to_string()), Op::Term("birds".to_string())]),
+            ])),
+            parse("cats AND (dogs OR birds)")
+        );
+    }
+
+    #[test]
+    fn bare_not_conjoins_with_preceding_clause_instead_of_joining_the_or_chain() {
+        assert_eq!(
+            Some(Op::And(vec![
+                Op::And(vec![
+                    Op::Term("cats".to_string()),
+                    Op::Or(vec![Op::Term("dogs".to_string()), Op::Term("birds".to_string())]),
+                ]),
+                Op::Not(Box::new(Op::Term("snakes".to_string()))),
+            ])),
+            parse("cats AND (dogs OR birds) NOT snakes")
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! Pluggable sources of documents for the `create` subcommand: a maildir
+//! walk, a CSV file, or a newline-delimited JSON file. Each implementation
+//! owns how its input is framed but yields the same flat `Record`s, so
+//! `analyze_record` and `IndexWriter::add` don't need to know which format
+//! a corpus came from (mirrors MeiliSearch's document-formats module).
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, prelude::*},
+    path::PathBuf,
+};
+
+use snafu::{ResultExt, Snafu};
+use walkdir::WalkDir;
+
+#[derive(Debug, Snafu)]
+pub enum SourceError {
+    UnableToReadMailFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    UnableToParseMail {
+        path: PathBuf,
+        source: mailparse::MailParseError,
+    },
+    UnableToOpenCsv {
+        path: PathBuf,
+        source: csv::Error,
+    },
+    UnableToReadCsvRecord {
+        path: PathBuf,
+        row: usize,
+        source: csv::Error,
+    },
+    UnableToOpenJsonl {
+        path: PathBuf,
+        source: io::Error,
+    },
+    UnableToReadJsonlLine {
+        path: PathBuf,
+        line: usize,
+        source: io::Error,
+    },
+    UnableToParseJsonlLine {
+        path: PathBuf,
+        line: usize,
+        source: serde_json::Error,
+    },
+    JsonlLineWasNotAnObject {
+        path: PathBuf,
+        line: usize,
+    },
+}
+
+pub type Fields = HashMap<String, String>;
+
+/// One document's worth of raw fields pulled from a `DocumentSource`.
+/// `identifier` names the record for error messages (a path, a row
+/// number, ...) - it isn't itself indexed or stored.
+pub struct Record {
+    pub identifier: String,
+    pub fields: Fields,
+}
+
+/// Yields the `Record`s that `create` feeds into `analyze_record`. Callers
+/// then decide which fields get tokenized (`searchable`) versus carried
+/// through untouched for display (`stored`) - the source itself doesn't
+/// know about that split.
+pub trait DocumentSource {
+    fn records(self: Box<Self>) -> Box<dyn Iterator<Item = Result<Record, SourceError>>>;
+}
+
+fn is_hidden(entry: &walkdir::DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .map(|s| s.starts_with('.'))
+        .unwrap_or(false)
+}
+
+/// Walks a maildir-style tree, treating each non-hidden file as one
+/// message whose body becomes the sole field, `"body"`.
+pub struct MailSource {
+    root: PathBuf,
+}
+
+impl MailSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        MailSource { root: root.into() }
+    }
+}
+
+impl DocumentSource for MailSource {
+    fn records(self: Box<Self>) -> Box<dyn Iterator<Item = Result<Record, SourceError>>> {
+        let paths: Vec<PathBuf> = WalkDir::new(&self.root)
+            .into_iter()
+            .filter_entry(|e| !is_hidden(e))
+            .filter_map(|e| e.ok())
+            .filter(|e| !e.file_type().is_dir())
+            .map(|e| e.path().to_path_buf())
+            .collect();
+
+        Box::new(paths.into_iter().map(|path| {
+            let content = fs::read(&path).context(UnableToReadMailFile { path: path.clone() })?;
+            let parsed =
+                mailparse::parse_mail(&content).context(UnableToParseMail { path: path.clone() })?;
+            let body = parsed
+                .get_body()
+                .context(UnableToParseMail { path: path.clone() })?
+                .trim()
+                .to_string();
+
+            let mut fields = Fields::new();
+            fields.insert("body".to_string(), body);
+
+            Ok(Record {
+                identifier: path.to_string_lossy().to_string(),
+                fields,
+            })
+        }))
+    }
+}
+
+/// Reads a CSV file, one record per row, field names taken from the
+/// header row.
+pub struct CsvSource {
+    path: PathBuf,
+}
+
+impl CsvSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        CsvSource { path: path.into() }
+    }
+}
+
+impl DocumentSource for CsvSource {
+    fn records(self: Box<Self>) -> Box<dyn Iterator<Item = Result<Record, SourceError>>> {
+        let path = self.path;
+
+        let mut reader = match csv::Reader::from_path(&path) {
+            Ok(reader) => reader,
+            Err(source) => {
+                return Box::new(std::iter::once(Err(SourceError::UnableToOpenCsv {
+                    path,
+                    source,
+                })))
+            }
+        };
+
+        let headers = match reader.headers() {
+            Ok(headers) => headers.clone(),
+            Err(source) => {
+                return Box::new(std::iter::once(Err(SourceError::UnableToOpenCsv {
+                    path,
+                    source,
+                })))
+            }
+        };
+
+        Box::new(
+            reader
+                .into_records()
+                .enumerate()
+                .map(move |(row, record)| {
+                    let record = record.context(UnableToReadCsvRecord {
+                        path: path.clone(),
+                        row,
+                    })?;
+
+                    let fields: Fields = headers
+                        .iter()
+                        .zip(record.iter())
+                        .map(|(name, value)| (name.to_string(), value.to_string()))
+                        .collect();
+
+                    Ok(Record {
+                        identifier: format!("row {}", row),
+                        fields,
+                    })
+                }),
+        )
+    }
+}
+
+/// Reads a newline-delimited JSON file, one record per line, each line a
+/// flat JSON object whose keys become field names. Blank lines are
+/// skipped.
+pub struct JsonlSource {
+    path: PathBuf,
+}
+
+impl JsonlSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonlSource { path: path.into() }
+    }
+}
+
+impl DocumentSource for JsonlSource {
+    fn records(self: Box<Self>) -> Box<dyn Iterator<Item = Result<Record, SourceError>>> {
+        let path = self.path;
+
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(source) => {
+                return Box::new(std::iter::once(Err(SourceError::UnableToOpenJsonl {
+                    path,
+                    source,
+                })))
+            }
+        };
+
+        Box::new(
+            io::BufReader::new(file)
+                .lines()
+                .enumerate()
+                .filter_map(move |(line, text)| {
+                    let text = match text.context(UnableToReadJsonlLine {
+                        path: path.clone(),
+                        line,
+                    }) {
+                        Ok(text) => text,
+                        Err(err) => return Some(Err(err)),
+                    };
+
+                    if text.trim().is_empty() {
+                        return None;
+                    }
+
+                    Some(parse_jsonl_line(&path, line, &text))
+                }),
+        )
+    }
+}
+
+fn parse_jsonl_line(path: &std::path::Path, line: usize, text: &str) -> Result<Record, SourceError> {
+    let value: serde_json::Value =
+        serde_json::from_str(text).context(UnableToParseJsonlLine { path, line })?;
+
+    let object = value.as_object().ok_or_else(|| SourceError::JsonlLineWasNotAnObject {
+        path: path.to_path_buf(),
+        line,
+    })?;
+
+    let fields: Fields = object
+        .iter()
+        .map(|(key, value)| (key.clone(), json_value_to_string(value)))
+        .collect();
+
+    Ok(Record {
+        identifier: format!("line {}", line),
+        fields,
+    })
+}
+
+/// Flattens a JSON scalar to the text it contributes; objects and arrays
+/// are serialized back to compact JSON rather than rejected, so one oddly
+/// nested field doesn't abort the whole import.
+fn json_value_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "rsearch-source-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file(dir: &PathBuf, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn records(source: impl DocumentSource + 'static) -> Vec<Result<Record, SourceError>> {
+        Box::new(source).records().collect()
+    }
+
+    #[test]
+    fn csv_source_yields_one_record_per_row_with_header_field_names() {
+        let dir = test_dir("csv-ok");
+        let path = write_file(&dir, "in.csv", "title,body\nFirst,one two\nSecond,three four\n");
+
+        let results = records(CsvSource::new(&path));
+        assert_eq!(2, results.len());
+
+        let first = results[0].as_ref().unwrap();
+        assert_eq!("row 0", first.identifier);
+        assert_eq!(Some(&"First".to_string()), first.fields.get("title"));
+        assert_eq!(Some(&"one two".to_string()), first.fields.get("body"));
+
+        let second = results[1].as_ref().unwrap();
+        assert_eq!("row 1", second.identifier);
+        assert_eq!(Some(&"Second".to_string()), second.fields.get("title"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn csv_source_fails_to_open_a_missing_file() {
+        let dir = test_dir("csv-missing");
+        let path = dir.join("does-not-exist.csv");
+
+        let results = records(CsvSource::new(&path));
+        assert_eq!(1, results.len());
+        assert!(matches!(results[0], Err(SourceError::UnableToOpenCsv { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn jsonl_source_skips_blank_lines() {
+        let dir = test_dir("jsonl-blank");
+        let path = write_file(
+            &dir,
+            "in.jsonl",
+            "{\"title\": \"First\"}\n\n   \n{\"title\": \"Second\"}\n",
+        );
+
+        let results = records(JsonlSource::new(&path));
+        let records: Vec<Record> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(2, records.len());
+        assert_eq!(Some(&"First".to_string()), records[0].fields.get("title"));
+        assert_eq!(Some(&"Second".to_string()), records[1].fields.get("title"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn jsonl_source_stringifies_nested_values() {
+        let dir = test_dir("jsonl-nested");
+        let path = write_file(&dir, "in.jsonl", "{\"tags\": [\"a\", \"b\"], \"count\": 3}\n");
+
+        let results = records(JsonlSource::new(&path));
+        let record = results.into_iter().next().unwrap().unwrap();
+        assert_eq!(Some(&"[\"a\",\"b\"]".to_string()), record.fields.get("tags"));
+        assert_eq!(Some(&"3".to_string()), record.fields.get("count"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn jsonl_source_rejects_a_line_that_is_not_an_object() {
+        let dir = test_dir("jsonl-not-object");
+        let path = write_file(&dir, "in.jsonl", "[1, 2, 3]\n");
+
+        let results = records(JsonlSource::new(&path));
+        assert_eq!(1, results.len());
+        assert!(matches!(
+            results[0],
+            Err(SourceError::JsonlLineWasNotAnObject { line: 0, .. })
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+//! Manages the scratch directory `IndexWriter` spills postings segments
+//! into when it's built `with_memory_limit`. Segment files live under one
+//! process-and-instance-unique directory under `std::env::temp_dir()`, and
+//! are cleaned up when the `TmpDir` is dropped.
+
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static NEXT_INSTANCE_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, PartialEq)]
+pub struct TmpDir {
+    path: PathBuf,
+    next_segment_id: usize,
+}
+
+impl TmpDir {
+    pub fn create() -> io::Result<Self> {
+        let instance_id = NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "rsearch-index-{}-{}",
+            std::process::id(),
+            instance_id
+        ));
+        fs::create_dir_all(&path)?;
+
+        Ok(TmpDir {
+            path,
+            next_segment_id: 0,
+        })
+    }
+
+    /// Returns a fresh path for the next segment file; doesn't create it.
+    pub fn next_segment_path(&mut self) -> PathBuf {
+        let path = self.path.join(format!("segment-{:08}.bin", self.next_segment_id));
+        self.next_segment_id += 1;
+        path
+    }
+}
+
+impl Drop for TmpDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_paths_are_distinct_and_increasing() {
+        let mut tmp = TmpDir::create().unwrap();
+        let first = tmp.next_segment_path();
+        let second = tmp.next_segment_path();
+        assert_ne!(first, second);
+        assert!(first.to_string_lossy().contains("00000000"));
+        assert!(second.to_string_lossy().contains("00000001"));
+    }
+
+    #[test]
+    fn directory_is_removed_on_drop() {
+        let mut tmp = TmpDir::create().unwrap();
+        let dir_path = tmp.next_segment_path().parent().unwrap().to_path_buf();
+        assert!(dir_path.exists());
+        drop(tmp);
+        assert!(!dir_path.exists());
+    }
+}